@@ -8,7 +8,7 @@ pub struct Book {
 impl house::query::Queryable for Book {
     fn query_terms(&self) -> Vec<house::query::Term> {
         vec![
-            house::query::Term { field: "title", value: self.title.as_bytes() },
+            house::query::Term { field: "title", value: self.title.as_bytes().into() },
         ]
     }
 }
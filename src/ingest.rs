@@ -0,0 +1,87 @@
+//! Batched ingestion: accumulate create/update/delete operations against a
+//! `Store` and commit them in as few sled transactions as possible.
+//!
+//! `create`/`update_multi`/`delete_multi` already each run in one
+//! transaction over `[tree, meta]`, but a caller juggling a mix of the
+//! three still pays one transaction per call. [`Ingest`] collects every
+//! operation and commits them all in a single `[tree, meta]` transaction.
+
+use crate::ser::SerDe;
+use crate::{err, query, Object, Store};
+
+enum Op<T> {
+    Create(T),
+    Update(Object<T>),
+    Delete(u64),
+}
+
+/// A builder that accumulates create/update/delete operations for `commit`.
+pub struct Ingest<'a, T, S = crate::ser::DefaultSerDe> {
+    store: &'a Store<T, S>,
+    ops: Vec<Op<T>>,
+}
+
+impl<'a, T, S> Ingest<'a, T, S> {
+    pub(crate) fn new(store: &'a Store<T, S>) -> Self {
+        Self { store, ops: Vec::new() }
+    }
+
+    pub fn create(mut self, inner: T) -> Self {
+        self.ops.push(Op::Create(inner));
+        self
+    }
+
+    pub fn update(mut self, object: Object<T>) -> Self {
+        self.ops.push(Op::Update(object));
+        self
+    }
+
+    pub fn delete(mut self, id: u64) -> Self {
+        self.ops.push(Op::Delete(id));
+        self
+    }
+}
+
+impl<'a, T: query::Queryable + serde::Serialize + serde::de::DeserializeOwned, S: SerDe>
+    Ingest<'a, T, S>
+{
+    /// Commits every accumulated operation in a single transaction over
+    /// `[tree, meta]`, returning the ids generated for `create` calls in
+    /// the order they were added.
+    pub fn commit(self) -> err::Result<Vec<u64>> {
+        let Self { store, ops } = self;
+
+        let mut created_ids = Vec::new();
+
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                Op::Create(inner) => {
+                    let id = store.db.generate_id()?;
+                    created_ids.push(id);
+                    Ok((id, Some(inner)))
+                }
+                Op::Update(Object { id, inner }) => Ok((id, Some(inner))),
+                Op::Delete(id) => Ok((id, None)),
+            })
+            .collect::<err::Result<Vec<_>>>()?;
+
+        use sled::transaction::Transactional;
+
+        &[&store.tree, &store.meta].transaction(|trees| {
+            let tree = &trees[0];
+            let meta = &trees[1];
+
+            for (id, inner) in &ops {
+                match inner {
+                    Some(inner) => Store::<T, S>::write_indexed(tree, meta, *id, inner)?,
+                    None => Store::<T, S>::delete_indexed(tree, meta, *id)?,
+                }
+            }
+
+            Ok(())
+        })?;
+
+        Ok(created_ids)
+    }
+}
@@ -39,20 +39,12 @@ pub mod err {
     pub type Result<T> = std::result::Result<T, Error>;
 }
 
-mod utils {
+/// Byte encoders/decoders for ids and for the order-preserving numeric
+/// fields used by [`query::Range`].
+pub mod utils {
 
     use std::convert::TryInto;
 
-    #[cfg(feature = "bincode")]
-    pub fn serialize<T: ?Sized + serde::Serialize>(value: &T) -> crate::err::Result<Vec<u8>> {
-        Ok(bincode::serialize(value)?)
-    }
-
-    #[cfg(feature = "bincode")]
-    pub fn deserialize<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> crate::err::Result<T> {
-        Ok(bincode::deserialize(bytes)?)
-    }
-
     pub fn u64_to_bytes(value: u64) -> [u8; 8] {
         u64::to_be_bytes(value)
     }
@@ -60,18 +52,42 @@ mod utils {
     pub fn bytes_to_u64(value: &[u8]) -> crate::err::Result<u64> {
         Ok(u64::from_be_bytes(value.try_into().map_err(crate::err::custom)?))
     }
+
+    pub fn u32_to_bytes(value: u32) -> [u8; 4] {
+        u32::to_be_bytes(value)
+    }
+
+    pub fn bytes_to_u32(value: &[u8]) -> crate::err::Result<u32> {
+        Ok(u32::from_be_bytes(value.try_into().map_err(crate::err::custom)?))
+    }
+
+    /// Encodes `value` so that unsigned big-endian byte ordering matches
+    /// numeric ordering: the sign bit is flipped so negative values sort
+    /// before non-negative ones.
+    pub fn i64_to_bytes(value: i64) -> [u8; 8] {
+        ((value as u64) ^ (1 << 63)).to_be_bytes()
+    }
+
+    pub fn bytes_to_i64(value: &[u8]) -> crate::err::Result<i64> {
+        let bits = u64::from_be_bytes(value.try_into().map_err(crate::err::custom)?);
+        Ok((bits ^ (1 << 63)) as i64)
+    }
 }
 
+pub mod ingest;
 pub mod query;
+pub mod ser;
 
 use sled::transaction::Transactional;
 use std::marker::PhantomData;
 
-pub struct Store<T> {
+use ser::SerDe;
+
+pub struct Store<T, S = ser::DefaultSerDe> {
     pub db: sled::Db,
     pub tree: sled::Tree,
     pub meta: sled::Tree,
-    pub marker: PhantomData<fn(T)>,
+    pub marker: PhantomData<fn(T, S)>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -94,35 +110,82 @@ impl<T> std::ops::DerefMut for Object<T> {
     }
 }
 
-impl<T: query::Queryable + serde::Serialize + serde::de::DeserializeOwned> Store<T> {
-    pub fn create(&self, inner: &T) -> err::Result<u64> {
-        let id = self.db.generate_id()?;
-
-        &[&self.tree, &self.meta].transaction(|trees| {
-            let id_bytes = utils::u64_to_bytes(id);
-
-            let tree = &trees[0];
-            let meta = &trees[1];
+impl<T: query::Queryable + serde::Serialize + serde::de::DeserializeOwned, S: SerDe> Store<T, S> {
+    /// Writes `inner` under `id` and reindexes its terms, removing whatever
+    /// terms it previously had. Shared by `create`, `update_multi`, and
+    /// [`ingest::Ingest`] so they can all run inside one `[tree, meta]`
+    /// transaction.
+    pub(crate) fn write_indexed(
+        tree: &sled::transaction::TransactionalTree,
+        meta: &sled::transaction::TransactionalTree,
+        id: u64,
+        inner: &T,
+    ) -> sled::transaction::ConflictableTransactionResult<(), err::Error> {
+        let id_bytes = utils::u64_to_bytes(id);
+
+        let serialized_inner = S::serialize(inner)?;
+
+        let new_terms =
+            inner.query_terms().into_iter().map(|t| t.flatten_with_id(id)).collect::<Vec<_>>();
+
+        let serialized_new_terms = S::serialize(&new_terms)?;
+
+        tree.insert(&id_bytes, serialized_inner)?;
+
+        let mut batch = sled::Batch::default();
+
+        if let Some(serialized_prev_terms) = meta.insert(
+            query::TERMS_PREFIX.into_iter().chain(&id_bytes).copied().collect::<Vec<_>>(),
+            serialized_new_terms,
+        )? {
+            let prev_terms: Vec<Vec<u8>> = S::deserialize(&serialized_prev_terms)?;
+            for term in prev_terms {
+                batch.remove(term);
+            }
+        }
 
-            let serialized_inner = utils::serialize(inner)?;
+        for term in new_terms {
+            batch.insert(term, sled::IVec::default());
+        }
 
-            let new_terms =
-                inner.query_terms().into_iter().map(|t| t.flatten_with_id(id)).collect::<Vec<_>>();
+        meta.apply_batch(&batch)?;
 
-            let serialized_new_terms = utils::serialize(&new_terms)?;
+        Ok(())
+    }
 
-            tree.insert(&id_bytes, serialized_inner)?;
+    /// Removes the document under `id` along with its indexed terms and
+    /// term list. Shared by `delete_multi` and [`ingest::Ingest`].
+    pub(crate) fn delete_indexed(
+        tree: &sled::transaction::TransactionalTree,
+        meta: &sled::transaction::TransactionalTree,
+        id: u64,
+    ) -> sled::transaction::ConflictableTransactionResult<(), err::Error> {
+        let id_bytes = utils::u64_to_bytes(id);
+
+        tree.remove(&id_bytes)?;
+
+        if let Some(serialized_terms) = meta.remove(
+            query::TERMS_PREFIX.into_iter().chain(&id_bytes).copied().collect::<Vec<_>>(),
+        )? {
+            let terms: Vec<Vec<u8>> = S::deserialize(&serialized_terms)?;
+
+            let mut batch = sled::Batch::default();
+            for term in terms {
+                batch.remove(term);
+            }
+            meta.apply_batch(&batch)?;
+        }
 
-            meta.insert(
-                query::TERMS_PREFIX.into_iter().chain(&id_bytes).copied().collect::<Vec<_>>(),
-                serialized_new_terms,
-            )?;
+        Ok(())
+    }
 
-            for term in new_terms {
-                meta.insert(term, sled::IVec::default())?;
-            }
+    pub fn create(&self, inner: &T) -> err::Result<u64> {
+        let id = self.db.generate_id()?;
 
-            Ok(())
+        &[&self.tree, &self.meta].transaction(|trees| {
+            let tree = &trees[0];
+            let meta = &trees[1];
+            Self::write_indexed(tree, meta, id, inner)
         })?;
 
         Ok(id)
@@ -138,33 +201,25 @@ impl<T: query::Queryable + serde::Serialize + serde::de::DeserializeOwned> Store
             let meta = &trees[1];
 
             for Object { id, inner } in objects {
+                Self::write_indexed(tree, meta, *id, inner)?;
+            }
 
-                let id_bytes = utils::u64_to_bytes(*id);
-
-                let serialized_inner = utils::serialize(inner)?;
-
-                let new_terms =
-                    inner.query_terms().into_iter().map(|t| t.flatten_with_id(*id)).collect::<Vec<_>>();
-
-                let serialized_new_terms = utils::serialize(&new_terms)?;
-
-                let mut batch = sled::Batch::default();
+            Ok(())
+        })?;
+        Ok(())
+    }
 
-                if let Some(serialized_prev_terms) = meta.insert(
-                    query::TERMS_PREFIX.into_iter().chain(&id_bytes).copied().collect::<Vec<_>>(),
-                    serialized_new_terms,
-                )? {
-                    let prev_terms: Vec<Vec<u8>> = utils::deserialize(&serialized_prev_terms)?;
-                    for term in prev_terms {
-                        batch.remove(term);
-                    }
-                }
+    pub fn delete(&self, id: u64) -> err::Result<()> {
+        self.delete_multi(&[id])
+    }
 
-                for term in new_terms {
-                    batch.insert(term, sled::IVec::default());
-                }
+    pub fn delete_multi(&self, ids: &[u64]) -> err::Result<()> {
+        &[&self.tree, &self.meta].transaction(|trees| {
+            let tree = &trees[0];
+            let meta = &trees[1];
 
-                meta.apply_batch(&batch)?;
+            for id in ids {
+                Self::delete_indexed(tree, meta, *id)?;
             }
 
             Ok(())
@@ -172,6 +227,12 @@ impl<T: query::Queryable + serde::Serialize + serde::de::DeserializeOwned> Store
         Ok(())
     }
 
+    /// Starts a batched ingest: accumulate create/update/delete operations
+    /// and commit them in one transaction. See [`ingest::Ingest`].
+    pub fn ingest(&self) -> ingest::Ingest<T, S> {
+        ingest::Ingest::new(self)
+    }
+
     pub fn all(&self) -> err::Result<Vec<Object<T>>> {
         
         Ok(self
@@ -181,7 +242,7 @@ impl<T: query::Queryable + serde::Serialize + serde::de::DeserializeOwned> Store
             .map(|(k, v)| {
                 Ok(Object {
                     id: utils::bytes_to_u64(k.as_ref())?,
-                    inner: utils::deserialize(&v)?,
+                    inner: S::deserialize(&v)?,
                 })
             })
             .collect::<err::Result<Vec<_>>>()?)
@@ -191,14 +252,14 @@ impl<T: query::Queryable + serde::Serialize + serde::de::DeserializeOwned> Store
         Ok(self
             .tree
             .get(utils::u64_to_bytes(id))?
-            .map(|bytes| utils::deserialize(&bytes))
+            .map(|bytes| S::deserialize(&bytes))
             .transpose()?
             .map(|inner| Object { id, inner }))
     }
 
-    pub fn filter<Q: query::Query>(&self, query: Q) -> err::Result<query::Results<T>> {
+    pub fn filter<Q: query::Query>(&self, query: Q) -> err::Result<query::Results<T, S>> {
         let matching_ids = query.matching_ids(self)?;
-        Ok(query::Results { matching_ids, store: self })
+        Ok(query::Results { matching_ids, store: self, order: None, skip: 0, limit: None })
     }
 
 }
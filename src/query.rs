@@ -1,80 +1,547 @@
-use crate::{err, utils, Store, Object};
-
-use std::collections::HashSet;
-
-pub(crate) const TERMS_PREFIX: &'static [u8] = b"__house__/terms/";
-
-pub struct Term<'a> {
-    pub field: &'a str,
-    pub value: &'a [u8],
-}
-
-impl<'a> Term<'a> {
-    pub(crate) fn flatten_with_id(self, id: u64) -> Vec<u8> {
-        let mut out = Vec::with_capacity(self.field.len() + self.value.len() + 8);
-        out.extend(self.field.as_bytes());
-        out.extend(self.value);
-        out.extend(&utils::u64_to_bytes(id));
-        out
-    }
-}
-
-pub trait Queryable {
-    fn query_terms(&self) -> Vec<Term>;
-}
-
-pub trait Query {
-    fn matching_ids<T>(&self, store: &Store<T>) -> err::Result<HashSet<u64>>;
-}
-
-pub struct StrEquals<'a>(pub &'a str, pub &'a str);
-
-impl<'a> Query for StrEquals<'a> {
-    fn matching_ids<T>(&self, store: &Store<T>) -> err::Result<HashSet<u64>> {
-
-        let prefix = self.0.as_bytes().into_iter().chain(self.1.as_bytes()).copied().collect::<Vec<_>>();
-
-        let prefix_len = prefix.len();
-
-        let mut out = HashSet::new();
-
-        for key in store.meta.scan_prefix(prefix).keys() {
-            let key = key?;
-            if let Ok(id) = utils::bytes_to_u64(&key[prefix_len..]) {
-                out.insert(id);
-            }
-        }
-
-        Ok(out)
-    }
-}
-
-pub struct Results<'a, T> {
-    pub(crate) store: &'a Store<T>,
-    pub(crate) matching_ids: HashSet<u64>,
-}
-
-impl<'a, T: Queryable + serde::Serialize + serde::de::DeserializeOwned> Results<'a, T> {
-
-    pub fn first(self) -> err::Result<Option<Object<T>>> {
-        let Self { store, matching_ids } = self;
-        Ok(matching_ids.into_iter().next()
-            .map(|id| store.find(id) )
-            .transpose()?
-            .and_then(|x| x))
-    }
-
-    pub fn all(self) -> err::Result<Vec<Object<T>>> {
-        let Self { store, matching_ids } = self;
-        let mut out = Vec::with_capacity(matching_ids.len());
-
-        for id in matching_ids.into_iter() {
-            if let Some(obj) = store.find(id)? {
-                out.push(obj);
-            }
-        }
-
-        Ok(out)
-    }
-
+use crate::{err, utils, Store, Object};
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::Bound;
+
+pub(crate) const TERMS_PREFIX: &'static [u8] = b"__house__/terms/";
+
+/// Separates the field name from the value in a flattened term key
+/// (`field ++ FIELD_SEP ++ value ++ id`). Without it, a field name that is
+/// a byte-prefix of another field's name (e.g. `"a"` vs `"ab"`) would have
+/// its keys bleed into that other field's prefix/range scans.
+const FIELD_SEP: &'static [u8] = &[0u8];
+
+/// Builds the `field ++ FIELD_SEP` prefix shared by every term key for
+/// `field`.
+pub(crate) fn field_prefix(field: &str) -> Vec<u8> {
+    field.as_bytes().iter().chain(FIELD_SEP).copied().collect()
+}
+
+pub struct Term<'a> {
+    pub field: &'a str,
+    pub value: Cow<'a, [u8]>,
+}
+
+impl<'a> Term<'a> {
+    pub(crate) fn flatten_with_id(self, id: u64) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.field.len() + FIELD_SEP.len() + self.value.len() + 8);
+        out.extend(self.field.as_bytes());
+        out.extend(FIELD_SEP);
+        out.extend(self.value.as_ref());
+        out.extend(&utils::u64_to_bytes(id));
+        out
+    }
+
+    /// Expand a single text field into one `Term` per token, using `tokenizer`
+    /// to split (and, by default, lowercase) `text`. Because `create` and
+    /// `update_multi` flatten every returned term into the `meta` tree, this
+    /// turns a whole-field value into many independently indexed tokens.
+    pub fn tokenized<TK: Tokenizer + ?Sized>(
+        field: &'a str,
+        text: &str,
+        tokenizer: &TK,
+    ) -> Vec<Term<'a>> {
+        tokenizer
+            .tokenize(text)
+            .into_iter()
+            .map(|token| Term { field, value: Cow::Owned(token.into_bytes()) })
+            .collect()
+    }
+}
+
+pub trait Queryable {
+    fn query_terms(&self) -> Vec<Term>;
+}
+
+/// Splits text into searchable tokens.
+pub trait Tokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String>;
+}
+
+/// Lowercases `text` and splits it on runs of non-alphanumeric characters,
+/// dropping empty tokens. This is a simple alphanumeric split, not full
+/// UAX#29 word-boundary segmentation -- punctuation inside a word (e.g.
+/// the apostrophe in `O'Brien`) splits the word instead of staying
+/// attached to it. Swap in a [`Tokenizer`] backed by a real segmentation
+/// crate if that distinction matters for your fields.
+pub struct DefaultTokenizer;
+
+impl Tokenizer for DefaultTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|token| !token.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+pub trait Query {
+    fn matching_ids<T, S>(&self, store: &Store<T, S>) -> err::Result<HashSet<u64>>;
+}
+
+pub struct StrEquals<'a>(pub &'a str, pub &'a str);
+
+impl<'a> Query for StrEquals<'a> {
+    fn matching_ids<T, S>(&self, store: &Store<T, S>) -> err::Result<HashSet<u64>> {
+
+        let prefix = field_prefix(self.0).into_iter().chain(self.1.as_bytes().iter().copied()).collect::<Vec<_>>();
+
+        let prefix_len = prefix.len();
+
+        let mut out = HashSet::new();
+
+        for key in store.meta.scan_prefix(prefix).keys() {
+            let key = key?;
+            if let Ok(id) = utils::bytes_to_u64(&key[prefix_len..]) {
+                out.insert(id);
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Matches documents whose `field` contains every token of `needle`
+/// (tokenized with [`DefaultTokenizer`], so it matches fields indexed via
+/// [`Term::tokenized`] with that tokenizer), i.e. AND semantics
+/// across tokens. An empty (or all-punctuation) needle matches nothing; a
+/// single-token needle behaves like [`StrEquals`]'s prefix-exact lookup.
+pub struct Contains<'a>(pub &'a str, pub &'a str);
+
+impl<'a> Query for Contains<'a> {
+    fn matching_ids<T, S>(&self, store: &Store<T, S>) -> err::Result<HashSet<u64>> {
+        let tokens = DefaultTokenizer.tokenize(self.1);
+
+        let mut tokens = tokens.into_iter();
+
+        let first = match tokens.next() {
+            Some(token) => token,
+            None => return Ok(HashSet::new()),
+        };
+
+        let mut out = Self::matching_ids_for_token(store, self.0, &first)?;
+
+        for token in tokens {
+            if out.is_empty() {
+                break;
+            }
+            let ids = Self::matching_ids_for_token(store, self.0, &token)?;
+            out.retain(|id| ids.contains(id));
+        }
+
+        Ok(out)
+    }
+}
+
+impl<'a> Contains<'a> {
+    fn matching_ids_for_token<T, S>(
+        store: &Store<T, S>,
+        field: &str,
+        token: &str,
+    ) -> err::Result<HashSet<u64>> {
+        let prefix = field_prefix(field).into_iter().chain(token.as_bytes().iter().copied()).collect::<Vec<_>>();
+
+        let prefix_len = prefix.len();
+
+        let mut out = HashSet::new();
+
+        for key in store.meta.scan_prefix(prefix).keys() {
+            let key = key?;
+            if let Ok(id) = utils::bytes_to_u64(&key[prefix_len..]) {
+                out.insert(id);
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Matches documents whose `field` falls within `[low, high)`, where `low`
+/// and `high` are already encoded with one of the fixed-width,
+/// order-preserving encoders in [`crate::utils`] (e.g. `u64_to_bytes`,
+/// `i64_to_bytes`, `u32_to_bytes`). Either bound may be omitted for an
+/// open range. `low` and `high`, when both present, must share the same
+/// byte width as each other and as the values indexed for `field` -- a
+/// mismatch silently drops rows instead of erroring at the storage layer.
+/// Bounds of different widths are rejected up front here; the width of
+/// the values indexed for `field` is not checked.
+pub struct Range<'a> {
+    pub field: &'a str,
+    pub low: Option<Vec<u8>>,
+    pub high: Option<Vec<u8>>,
+}
+
+impl<'a> Query for Range<'a> {
+    fn matching_ids<T, S>(&self, store: &Store<T, S>) -> err::Result<HashSet<u64>> {
+        if let (Some(low), Some(high)) = (&self.low, &self.high) {
+            if low.len() != high.len() {
+                return Err(err::custom(
+                    "Range low and high bounds must be encoded with the same fixed byte width",
+                ));
+            }
+        }
+
+        let field_prefix = field_prefix(self.field);
+
+        let start = match &self.low {
+            Some(low) => {
+                Bound::Included(field_prefix.iter().chain(low).copied().collect::<Vec<u8>>())
+            }
+            None => Bound::Included(field_prefix.clone()),
+        };
+
+        let end = match &self.high {
+            Some(high) => {
+                Bound::Excluded(field_prefix.iter().chain(high).copied().collect::<Vec<u8>>())
+            }
+            None => Bound::Unbounded,
+        };
+
+        let mut out = HashSet::new();
+
+        for key in store.meta.range((start, end)).keys() {
+            let key = key?;
+            if !key.starts_with(field_prefix.as_slice()) {
+                break;
+            }
+
+            let id_offset = key.len() - 8;
+            if let Ok(id) = utils::bytes_to_u64(&key[id_offset..]) {
+                out.insert(id);
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Matches documents returned by both `A` and `B`.
+pub struct And<A, B>(pub A, pub B);
+
+/// Matches documents returned by either `A` or `B`.
+pub struct Or<A, B>(pub A, pub B);
+
+/// Matches every document except those returned by `A`.
+pub struct Not<A>(pub A);
+
+impl<A: Query, B: Query> Query for And<A, B> {
+    fn matching_ids<T, S>(&self, store: &Store<T, S>) -> err::Result<HashSet<u64>> {
+        let a = self.0.matching_ids(store)?;
+        let b = self.1.matching_ids(store)?;
+        Ok(a.intersection(&b).copied().collect())
+    }
+}
+
+impl<A: Query, B: Query> Query for Or<A, B> {
+    fn matching_ids<T, S>(&self, store: &Store<T, S>) -> err::Result<HashSet<u64>> {
+        let mut a = self.0.matching_ids(store)?;
+        a.extend(self.1.matching_ids(store)?);
+        Ok(a)
+    }
+}
+
+impl<A: Query> Query for Not<A> {
+    fn matching_ids<T, S>(&self, store: &Store<T, S>) -> err::Result<HashSet<u64>> {
+        let matches = self.0.matching_ids(store)?;
+
+        let mut out = HashSet::new();
+        for key in store.tree.iter().keys() {
+            let id = utils::bytes_to_u64(&key?)?;
+            if !matches.contains(&id) {
+                out.insert(id);
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Blanket combinators for building a query algebra out of [`Query`] values,
+/// e.g. `StrEquals("title", "X").and(Contains("desc", "man"))`.
+pub trait QueryExt: Query + Sized {
+    fn and<B: Query>(self, other: B) -> And<Self, B> {
+        And(self, other)
+    }
+
+    fn or<B: Query>(self, other: B) -> Or<Self, B> {
+        Or(self, other)
+    }
+
+    fn not(self) -> Not<Self> {
+        Not(self)
+    }
+}
+
+impl<Q: Query> QueryExt for Q {}
+
+/// Sort direction for [`Results::order_by`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Asc,
+    Desc,
+}
+
+pub struct Results<'a, T, S = crate::ser::DefaultSerDe> {
+    pub(crate) store: &'a Store<T, S>,
+    pub(crate) matching_ids: HashSet<u64>,
+    pub(crate) order: Option<(String, Direction)>,
+    pub(crate) skip: usize,
+    pub(crate) limit: Option<usize>,
+}
+
+impl<'a, T, S> Results<'a, T, S> {
+    /// Sorts matches by the indexed bytes of `field`, read from the `meta`
+    /// tree rather than by loading every matching document; only matches
+    /// with no indexed term for `field` are loaded. The collected values
+    /// are then sorted, and the order of matches with equal values is not
+    /// defined.
+    pub fn order_by(mut self, field: &str, direction: Direction) -> Self {
+        self.order = Some((field.to_string(), direction));
+        self
+    }
+
+    pub fn skip(mut self, n: usize) -> Self {
+        self.skip = n;
+        self
+    }
+
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+}
+
+impl<'a, T: Queryable + serde::Serialize + serde::de::DeserializeOwned, S: crate::ser::SerDe>
+    Results<'a, T, S>
+{
+    fn ordered_ids(&self) -> err::Result<Vec<u64>> {
+        let (field, direction) = match &self.order {
+            Some(order) => order,
+            None => return Ok(self.matching_ids.iter().copied().collect()),
+        };
+
+        let field_prefix = field_prefix(field);
+        let prefix_len = field_prefix.len();
+
+        // id -> value bytes of `field` for each matching id. A HashMap (rather than
+        // pushing straight into a Vec) is what lets a multi-valued field
+        // (e.g. a `Contains`-tokenized one, which emits several `Term`s per
+        // id) dedupe to one entry per id instead of one per token.
+        let mut values = std::collections::HashMap::new();
+
+        for key in self.store.meta.scan_prefix(&field_prefix).keys() {
+            let key = key?;
+            if key.len() < prefix_len + 8 {
+                continue;
+            }
+            let id_offset = key.len() - 8;
+            if let Ok(id) = utils::bytes_to_u64(&key[id_offset..]) {
+                if self.matching_ids.contains(&id) {
+                    // scan_prefix visits keys in ascending order, so the
+                    // first value seen for an id is its lexicographically
+                    // smallest for this field -- keep that one.
+                    values.entry(id).or_insert_with(|| key[prefix_len..id_offset].to_vec());
+                }
+            }
+        }
+
+        // A matching id whose document has no term for `field` at all (it
+        // was never in `query_terms()`, or under a different encoding)
+        // never shows up in the scan above. Rather than silently dropping
+        // it, load the document and look for the term directly, falling
+        // back to an empty value -- which sorts before everything else --
+        // if the field is genuinely absent.
+        for id in self.matching_ids.iter().copied() {
+            if values.contains_key(&id) {
+                continue;
+            }
+
+            let value = self
+                .store
+                .find(id)?
+                .and_then(|object| {
+                    object
+                        .inner
+                        .query_terms()
+                        .into_iter()
+                        .find(|term| term.field == field.as_str())
+                        .map(|term| term.value.into_owned())
+                })
+                .unwrap_or_default();
+
+            values.insert(id, value);
+        }
+
+        let mut ids = values.keys().copied().collect::<Vec<_>>();
+        ids.sort_by(|a, b| values[a].cmp(&values[b]));
+
+        if *direction == Direction::Desc {
+            ids.reverse();
+        }
+
+        Ok(ids)
+    }
+
+    fn paginated_ids(&self) -> err::Result<Vec<u64>> {
+        let ids = self.ordered_ids()?.into_iter().skip(self.skip);
+        Ok(match self.limit {
+            Some(limit) => ids.take(limit).collect(),
+            None => ids.collect(),
+        })
+    }
+
+    /// Number of matches after `order_by`/`skip`/`limit` are applied,
+    /// without loading any documents.
+    pub fn count(&self) -> err::Result<usize> {
+        // Ordering never adds or drops a match, so the count depends only
+        // on `skip`/`limit`.
+        let remaining = self.matching_ids.len().saturating_sub(self.skip);
+        Ok(match self.limit {
+            Some(limit) => remaining.min(limit),
+            None => remaining,
+        })
+    }
+
+    pub fn first(self) -> err::Result<Option<Object<T>>> {
+        self.paginated_ids()?
+            .into_iter()
+            .next()
+            .map(|id| self.store.find(id))
+            .transpose()
+            .map(|x| x.flatten())
+    }
+
+    pub fn all(self) -> err::Result<Vec<Object<T>>> {
+        let ids = self.paginated_ids()?;
+        let mut out = Vec::with_capacity(ids.len());
+
+        for id in ids {
+            if let Some(obj) = self.store.find(id)? {
+                out.push(obj);
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_store<T>() -> Store<T> {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let tree = db.open_tree(b"tree").unwrap();
+        let meta = db.open_tree(b"meta").unwrap();
+        Store { db, tree, meta, marker: std::marker::PhantomData }
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct FieldValue {
+        field: String,
+        value: String,
+    }
+
+    impl Queryable for FieldValue {
+        fn query_terms(&self) -> Vec<Term> {
+            vec![Term { field: self.field.as_str(), value: self.value.as_bytes().into() }]
+        }
+    }
+
+    #[test]
+    fn field_names_that_prefix_each_other_do_not_collide() {
+        let store = open_store::<FieldValue>();
+
+        let a_id =
+            store.create(&FieldValue { field: "a".into(), value: "x".into() }).unwrap();
+        let _ab_id =
+            store.create(&FieldValue { field: "ab".into(), value: "x".into() }).unwrap();
+
+        let ids = store
+            .filter(StrEquals("a", "x"))
+            .unwrap()
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|o| o.id)
+            .collect::<HashSet<_>>();
+        assert_eq!(ids, [a_id].into_iter().collect());
+
+        let ids = store
+            .filter(Contains("a", "x"))
+            .unwrap()
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|o| o.id)
+            .collect::<HashSet<_>>();
+        assert_eq!(ids, [a_id].into_iter().collect());
+
+        let ids = store
+            .filter(Range { field: "a", low: None, high: None })
+            .unwrap()
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|o| o.id)
+            .collect::<HashSet<_>>();
+        assert_eq!(ids, [a_id].into_iter().collect());
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Doc {
+        title: String,
+        body: String,
+    }
+
+    impl Queryable for Doc {
+        fn query_terms(&self) -> Vec<Term> {
+            Term::tokenized("body", &self.body, &DefaultTokenizer)
+        }
+    }
+
+    #[test]
+    fn order_by_dedupes_multi_valued_fields_and_does_not_drop_missing_ones() {
+        let store = open_store::<Doc>();
+
+        // tokenizes to three terms under "body" -- should still appear once
+        let multi_id = store
+            .create(&Doc { title: "one".into(), body: "alpha beta gamma".into() })
+            .unwrap();
+        let single_id =
+            store.create(&Doc { title: "two".into(), body: "delta".into() }).unwrap();
+        // empty body tokenizes to zero terms -- "body" is entirely absent
+        // for this document, so it must still show up (sorted first)
+        // instead of being silently dropped
+        let no_terms_id =
+            store.create(&Doc { title: "three".into(), body: "".into() }).unwrap();
+
+        // `Not` over an impossible title match is a convenient way to get
+        // every id back as `matching_ids`.
+        let ordered = store
+            .filter(Not(StrEquals("title", "missing")))
+            .unwrap()
+            .order_by("body", Direction::Asc)
+            .all()
+            .unwrap()
+            .into_iter()
+            .map(|o| o.id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ordered, vec![no_terms_id, multi_id, single_id]);
+
+        let page = store
+            .filter(Not(StrEquals("title", "missing")))
+            .unwrap()
+            .order_by("body", Direction::Asc)
+            .skip(1)
+            .limit(1)
+            .all()
+            .unwrap();
+
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, multi_id);
+    }
 }
\ No newline at end of file
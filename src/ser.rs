@@ -0,0 +1,50 @@
+//! Pluggable (de)serialization backends for [`crate::Store`].
+//!
+//! Previously document encoding was hard-wired to whichever of the
+//! `bincode`/`serde_cbor` features was enabled, and enabling both was
+//! ambiguous. [`SerDe`] makes the backend a type parameter on `Store`
+//! instead, so a single binary can keep some trees in bincode and others
+//! in CBOR. The `meta` term-list encoding uses the same `SerDe` as the
+//! document itself, so round-tripping stays consistent.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub trait SerDe {
+    fn serialize<T: Serialize>(value: &T) -> crate::err::Result<Vec<u8>>;
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> crate::err::Result<T>;
+}
+
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl SerDe for Bincode {
+    fn serialize<T: Serialize>(value: &T) -> crate::err::Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> crate::err::Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+#[cfg(feature = "serde_cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "serde_cbor")]
+impl SerDe for Cbor {
+    fn serialize<T: Serialize>(value: &T) -> crate::err::Result<Vec<u8>> {
+        Ok(serde_cbor::to_vec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> crate::err::Result<T> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+}
+
+#[cfg(feature = "bincode")]
+pub type DefaultSerDe = Bincode;
+
+#[cfg(all(not(feature = "bincode"), feature = "serde_cbor"))]
+pub type DefaultSerDe = Cbor;